@@ -0,0 +1,241 @@
+//! Builds a structured expression tree directly from tokens, using a
+//! [binding power](https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html)
+//! parser equivalent to the shunting-yard algorithm in `parse`.
+use crate::{Op, ParsingError, Spanned, Token};
+
+/// A parsed arithmetic expression tree.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Expr {
+    Number(i32),
+    Bool(bool),
+    Neg(Box<Expr>),
+    BinOp {
+        op: Op,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+}
+
+/// Binding power of the unary prefix minus, binding tighter than any binary
+/// operator below.
+const PREFIX_BP: u8 = 9;
+
+impl Op {
+    /// Left and right binding power. Left-associative operators bind their
+    /// right-hand side slightly tighter than their left (`left < right`);
+    /// a right-associative operator like `Pow` flips the two instead.
+    /// Comparisons bind more loosely than `+`/`-`.
+    fn binding_power(&self) -> (u8, u8) {
+        use Op::*;
+
+        match self {
+            Eq | NotEq | Lt | Gt | Le | Ge => (0, 1),
+            Add | Sub => (2, 3),
+            Mul | Div => (4, 5),
+            Pow => (7, 6),
+            Neg => unreachable!("Neg is a prefix operator and has no infix binding power"),
+        }
+    }
+}
+
+/// Parses a token stream (as produced by `lex`) into an `Expr` tree.
+pub fn parse_expr(tokens: &[Spanned<Token>]) -> Result<Expr, ParsingError> {
+    let mut pos = 0;
+    parse_bp(tokens, &mut pos, 0)
+}
+
+/// The span to blame when `pos` runs past the end of `tokens`.
+fn eof_span(tokens: &[Spanned<Token>]) -> std::ops::Range<usize> {
+    let end = tokens.last().map(|tok| tok.span.end).unwrap_or(0);
+    end..end
+}
+
+fn parse_bp(tokens: &[Spanned<Token>], pos: &mut usize, min_bp: u8) -> Result<Expr, ParsingError> {
+    let mut lhs = match tokens.get(*pos) {
+        Some(Spanned { value: Token::Number(n), .. }) => {
+            let n = *n;
+            *pos += 1;
+            Expr::Number(n)
+        }
+        Some(Spanned { value: Token::Bool(b), .. }) => {
+            let b = *b;
+            *pos += 1;
+            Expr::Bool(b)
+        }
+        Some(Spanned { value: Token::LeftParen, .. }) => {
+            *pos += 1;
+            let inner = parse_bp(tokens, pos, 0)?;
+            match tokens.get(*pos) {
+                Some(Spanned { value: Token::RightParen, .. }) => *pos += 1,
+                Some(tok) => return Err(ParsingError::NoMatchingParen { span: tok.span.clone() }),
+                None => return Err(ParsingError::NoMatchingParen { span: eof_span(tokens) }),
+            }
+            inner
+        }
+        Some(Spanned { value: Token::Operation(Op::Neg), .. }) => {
+            *pos += 1;
+            Expr::Neg(Box::new(parse_bp(tokens, pos, PREFIX_BP)?))
+        }
+        Some(tok) => return Err(ParsingError::BadInput { span: tok.span.clone() }),
+        None => return Err(ParsingError::BadInput { span: eof_span(tokens) }),
+    };
+
+    while let Some(Spanned { value: Token::Operation(op), .. }) = tokens.get(*pos) {
+        let op = *op;
+        let (left_bp, right_bp) = op.binding_power();
+        if left_bp < min_bp {
+            break;
+        }
+
+        *pos += 1;
+        let rhs = parse_bp(tokens, pos, right_bp)?;
+        lhs = Expr::BinOp {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        };
+    }
+
+    Ok(lhs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex;
+    use crate::Op::*;
+
+    #[test]
+    fn single_number() {
+        assert_eq!(parse_expr(&lex("42").unwrap()).unwrap(), Expr::Number(42));
+    }
+
+    #[test]
+    fn left_associative() {
+        // 3 - 4 - 5 should parse as (3 - 4) - 5, not 3 - (4 - 5).
+        let tree = parse_expr(&lex("3 - 4 - 5").unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            Expr::BinOp {
+                op: Sub,
+                lhs: Box::new(Expr::BinOp {
+                    op: Sub,
+                    lhs: Box::new(Expr::Number(3)),
+                    rhs: Box::new(Expr::Number(4)),
+                }),
+                rhs: Box::new(Expr::Number(5)),
+            }
+        );
+    }
+
+    #[test]
+    fn precedence_and_parens() {
+        let tree = parse_expr(&lex("3 + 4 * 5").unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            Expr::BinOp {
+                op: Add,
+                lhs: Box::new(Expr::Number(3)),
+                rhs: Box::new(Expr::BinOp {
+                    op: Mul,
+                    lhs: Box::new(Expr::Number(4)),
+                    rhs: Box::new(Expr::Number(5)),
+                }),
+            }
+        );
+
+        let parenthesized = parse_expr(&lex("(3 + 4) * 5").unwrap()).unwrap();
+        assert_eq!(
+            parenthesized,
+            Expr::BinOp {
+                op: Mul,
+                lhs: Box::new(Expr::BinOp {
+                    op: Add,
+                    lhs: Box::new(Expr::Number(3)),
+                    rhs: Box::new(Expr::Number(4)),
+                }),
+                rhs: Box::new(Expr::Number(5)),
+            }
+        );
+    }
+
+    #[test]
+    fn unmatched_paren_is_an_error() {
+        assert!(parse_expr(&lex("(3 + 4").unwrap()).is_err());
+    }
+
+    #[test]
+    fn unary_minus_binds_tighter_than_addition() {
+        let tree = parse_expr(&lex("-3 + 4").unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            Expr::BinOp {
+                op: Add,
+                lhs: Box::new(Expr::Neg(Box::new(Expr::Number(3)))),
+                rhs: Box::new(Expr::Number(4)),
+            }
+        );
+    }
+
+    #[test]
+    fn unary_minus_can_apply_to_a_parenthesized_expression() {
+        let tree = parse_expr(&lex("-(3 + 4)").unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            Expr::Neg(Box::new(Expr::BinOp {
+                op: Add,
+                lhs: Box::new(Expr::Number(3)),
+                rhs: Box::new(Expr::Number(4)),
+            }))
+        );
+    }
+
+    #[test]
+    fn pow_is_right_associative() {
+        // 2 ^ 3 ^ 2 should parse as 2 ^ (3 ^ 2), not (2 ^ 3) ^ 2.
+        let tree = parse_expr(&lex("2 ^ 3 ^ 2").unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            Expr::BinOp {
+                op: Pow,
+                lhs: Box::new(Expr::Number(2)),
+                rhs: Box::new(Expr::BinOp {
+                    op: Pow,
+                    lhs: Box::new(Expr::Number(3)),
+                    rhs: Box::new(Expr::Number(2)),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn comparisons_bind_looser_than_addition() {
+        // 1 + 2 < 4 should parse as (1 + 2) < 4, not 1 + (2 < 4).
+        let tree = parse_expr(&lex("1 + 2 < 4").unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            Expr::BinOp {
+                op: Lt,
+                lhs: Box::new(Expr::BinOp {
+                    op: Add,
+                    lhs: Box::new(Expr::Number(1)),
+                    rhs: Box::new(Expr::Number(2)),
+                }),
+                rhs: Box::new(Expr::Number(4)),
+            }
+        );
+    }
+
+    #[test]
+    fn bool_literals_parse() {
+        assert_eq!(parse_expr(&lex("true").unwrap()).unwrap(), Expr::Bool(true));
+        assert_eq!(
+            parse_expr(&lex("1 == 1").unwrap()).unwrap(),
+            Expr::BinOp {
+                op: Eq,
+                lhs: Box::new(Expr::Number(1)),
+                rhs: Box::new(Expr::Number(1)),
+            }
+        );
+    }
+}
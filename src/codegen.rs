@@ -0,0 +1,281 @@
+//! Lowers parsed RPN (postfix) token streams into instructions for a small
+//! stack/register abstract machine.
+use std::error::Error;
+use std::fmt;
+
+use crate::{Op, Token};
+
+/// The registers available on the abstract machine.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Reg {
+    Ax,
+    Bx,
+    Cx,
+    Dx,
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reg::Ax => write!(f, "ax"),
+            Reg::Bx => write!(f, "bx"),
+            Reg::Cx => write!(f, "cx"),
+            Reg::Dx => write!(f, "dx"),
+        }
+    }
+}
+
+/// Registers are allocated in this order as expression depth grows.
+const REGS: [Reg; 4] = [Reg::Ax, Reg::Bx, Reg::Cx, Reg::Dx];
+
+/// Something an instruction can read: either an immediate or a register.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Operand {
+    Imm(i32),
+    Reg(Reg),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Imm(n) => write!(f, "{}", n),
+            Operand::Reg(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+/// A single instruction for the register machine. ALU instructions read as
+/// `dst op= src`, e.g. `Sub(Bx, Ax)` means `bx -= ax`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Instr {
+    Push(Operand),
+    Pop(Reg),
+    Add(Reg, Reg),
+    Sub(Reg, Reg),
+    Mult(Reg, Reg),
+    Div(Reg, Reg),
+    /// `dst %= src`. Emitted right before the matching `Div` (while `dst`
+    /// still holds the dividend) so the remainder lands in `cx` the way real
+    /// `div`/`idiv` instructions deposit it alongside the quotient.
+    Mod(Reg, Reg),
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Push(src) => write!(f, "push {}", src),
+            Instr::Pop(dst) => write!(f, "pop {}", dst),
+            Instr::Add(dst, src) => write!(f, "add {} {}", dst, src),
+            Instr::Sub(dst, src) => write!(f, "sub {} {}", dst, src),
+            Instr::Mult(dst, src) => write!(f, "mult {} {}", dst, src),
+            Instr::Div(dst, src) => write!(f, "div {} {}", dst, src),
+            Instr::Mod(dst, src) => write!(f, "mod {} {}", dst, src),
+        }
+    }
+}
+
+/// All possible codegen errors.
+#[derive(Debug, Eq, PartialEq)]
+pub enum CompileError {
+    DivisionByZero,
+    /// The register machine has no instruction for this operator yet.
+    Unsupported(Op),
+    /// The register machine has no notion of named storage yet.
+    Variable(String),
+    /// The register machine only knows how to hold integers.
+    Boolean,
+    /// The expression needs more live operands at once than there are
+    /// registers; register spilling is not implemented.
+    RegisterSpill,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::DivisionByZero => write!(f, "division by zero"),
+            CompileError::Unsupported(op) => {
+                write!(f, "{:?} has no register-machine instruction yet", op)
+            }
+            CompileError::Variable(name) => {
+                write!(f, "the register machine does not support variables yet (saw `{}`)", name)
+            }
+            CompileError::Boolean => {
+                write!(f, "the register machine has no representation for booleans yet")
+            }
+            CompileError::RegisterSpill => {
+                write!(f, "expression is too deep for the {} available registers", REGS.len())
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// Lowers a postfix token stream into instructions for the register machine.
+///
+/// Each `Number` pushes an immediate; each `Operation` pops its two operand
+/// slots into the next pair of registers (allocated by expression depth),
+/// emits the matching ALU instruction, and pushes the result register back
+/// onto the stack. `div` always leaves its remainder in `cx` via an extra
+/// `Mod` instruction, discarded if `cx` happened to be the destination.
+/// Returns `CompileError::RegisterSpill` if an expression needs more than
+/// `REGS.len()` live operands at once, since this machine has no spill slots.
+pub fn compile(postfix: &[Token]) -> Result<Vec<Instr>, CompileError> {
+    let mut instrs = Vec::new();
+    // Tracks the literal value carried by each pushed slot so we can catch
+    // division by zero; this mirrors what the machine would compute at
+    // runtime since every operand here is a constant.
+    let mut values: Vec<i32> = Vec::new();
+
+    for tok in postfix {
+        match tok {
+            Token::Number(n) => {
+                instrs.push(Instr::Push(Operand::Imm(*n)));
+                values.push(*n);
+            }
+            Token::Bool(_) => return Err(CompileError::Boolean),
+            Token::Ident(name) => return Err(CompileError::Variable(name.clone())),
+            Token::Operation(
+                op @ (Op::Neg | Op::Pow | Op::Eq | Op::NotEq | Op::Lt | Op::Gt | Op::Le | Op::Ge),
+            ) => {
+                return Err(CompileError::Unsupported(*op));
+            }
+            Token::Operation(op) => {
+                let rhs = values.pop().expect("parser guarantees well-formed RPN");
+                let lhs = values.pop().expect("parser guarantees well-formed RPN");
+                let depth = values.len();
+                if depth + 1 >= REGS.len() {
+                    return Err(CompileError::RegisterSpill);
+                }
+                let dst = REGS[depth];
+                let src = REGS[depth + 1];
+
+                instrs.push(Instr::Pop(src));
+                instrs.push(Instr::Pop(dst));
+
+                let result = match op {
+                    Op::Add => {
+                        instrs.push(Instr::Add(dst, src));
+                        lhs + rhs
+                    }
+                    Op::Sub => {
+                        instrs.push(Instr::Sub(dst, src));
+                        lhs - rhs
+                    }
+                    Op::Mul => {
+                        instrs.push(Instr::Mult(dst, src));
+                        lhs * rhs
+                    }
+                    Op::Div => {
+                        if rhs == 0 {
+                            return Err(CompileError::DivisionByZero);
+                        }
+                        let mut divisor = src;
+                        if src == Reg::Cx {
+                            // `cx` is about to receive the remainder, so move
+                            // the divisor out of its way into `dx` first.
+                            instrs.push(Instr::Push(Operand::Reg(Reg::Cx)));
+                            instrs.push(Instr::Pop(Reg::Dx));
+                            divisor = Reg::Dx;
+                        }
+                        if dst != Reg::Cx {
+                            // Stash the dividend in `cx` and take its
+                            // remainder there before `Div` overwrites `dst`
+                            // with the quotient.
+                            instrs.push(Instr::Push(Operand::Reg(dst)));
+                            instrs.push(Instr::Pop(Reg::Cx));
+                            instrs.push(Instr::Mod(Reg::Cx, divisor));
+                        }
+                        instrs.push(Instr::Div(dst, divisor));
+                        lhs / rhs
+                    }
+                    Op::Neg | Op::Pow | Op::Eq | Op::NotEq | Op::Lt | Op::Gt | Op::Le | Op::Ge => {
+                        unreachable!("handled above")
+                    }
+                };
+
+                instrs.push(Instr::Push(Operand::Reg(dst)));
+                values.push(result);
+            }
+            Token::LeftParen | Token::RightParen => {
+                unreachable!("{:?} must not appear in parsed RPN", tok)
+            }
+            Token::Keyword(_) | Token::Assign | Token::Semicolon => {
+                unreachable!("{:?} must not appear in parsed RPN", tok)
+            }
+            Token::EOF => break,
+        }
+    }
+
+    Ok(instrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lex, parse};
+
+    fn compile_str(input: &str) -> Result<Vec<Instr>, CompileError> {
+        let postfix: Vec<Token> = parse(lex(input).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|s| s.value)
+            .collect();
+        compile(&postfix)
+    }
+
+    #[test]
+    fn basic_add() {
+        assert_eq!(
+            compile_str("3 + 4").unwrap(),
+            vec![
+                Instr::Push(Operand::Imm(3)),
+                Instr::Push(Operand::Imm(4)),
+                Instr::Pop(Reg::Bx),
+                Instr::Pop(Reg::Ax),
+                Instr::Add(Reg::Ax, Reg::Bx),
+                Instr::Push(Operand::Reg(Reg::Ax)),
+            ]
+        );
+    }
+
+    #[test]
+    fn division_deposits_its_remainder_in_cx() {
+        let instrs = compile_str("7 / 2").unwrap();
+        assert_eq!(
+            instrs,
+            vec![
+                Instr::Push(Operand::Imm(7)),
+                Instr::Push(Operand::Imm(2)),
+                Instr::Pop(Reg::Bx),
+                Instr::Pop(Reg::Ax),
+                Instr::Push(Operand::Reg(Reg::Ax)),
+                Instr::Pop(Reg::Cx),
+                Instr::Mod(Reg::Cx, Reg::Bx),
+                Instr::Div(Reg::Ax, Reg::Bx),
+                Instr::Push(Operand::Reg(Reg::Ax)),
+            ]
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert_eq!(compile_str("1 / 0"), Err(CompileError::DivisionByZero));
+    }
+
+    #[test]
+    fn deeply_nested_expression_reports_register_spill_instead_of_panicking() {
+        // Right-nesting forces every number onto the RPN stream before any
+        // `Add` is resolved, so this needs five live values at once -- more
+        // than the four available registers.
+        assert_eq!(
+            compile_str("(1 + (2 + (3 + (4 + 5))))"),
+            Err(CompileError::RegisterSpill)
+        );
+    }
+
+    #[test]
+    fn unsupported_operator_is_reported() {
+        assert_eq!(compile_str("2 ^ 3"), Err(CompileError::Unsupported(Op::Pow)));
+    }
+}
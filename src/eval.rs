@@ -0,0 +1,331 @@
+//! Tree-walks a postfix token stream to fold it down to a single value.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{parse, Keyword, Op, ParsingError, Spanned, Token};
+
+/// A runtime value. Arithmetic produces `Int`s, comparisons produce `Bool`s,
+/// and the two never mix.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Value {
+    Int(i32),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// All possible evaluation errors.
+#[derive(Debug, Eq, PartialEq)]
+pub enum EvalError {
+    DivisionByZero,
+    MalformedExpression,
+    UndefinedVariable(String),
+    /// An operator was applied to a value of the wrong kind, e.g. adding two
+    /// booleans or comparing an `Int` to a `Bool`.
+    TypeError(String),
+    /// `Pow` only knows how to raise to a non-negative power; `i32` has no
+    /// fractional values to represent the alternative.
+    NegativeExponent,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::MalformedExpression => write!(f, "malformed expression"),
+            EvalError::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            EvalError::TypeError(why) => write!(f, "type error: {}", why),
+            EvalError::NegativeExponent => write!(f, "cannot raise to a negative power"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+/// Runs the classic postfix-evaluation loop: push every literal, and for
+/// every `Operation` pop the right operand then the left operand, apply the
+/// op, and push the result back. `Neg` is unary, so it only pops one operand.
+/// Identifiers are looked up in `env`, the bindings built up so far by `run`.
+pub fn eval(postfix: &[Token], env: &HashMap<String, Value>) -> Result<Value, EvalError> {
+    let mut stack: Vec<Value> = Vec::new();
+
+    for tok in postfix {
+        match tok {
+            Token::Number(n) => stack.push(Value::Int(*n)),
+            Token::Bool(b) => stack.push(Value::Bool(*b)),
+            Token::Ident(name) => {
+                let value = *env
+                    .get(name)
+                    .ok_or_else(|| EvalError::UndefinedVariable(name.clone()))?;
+                stack.push(value);
+            }
+            Token::Operation(Op::Neg) => {
+                let val = stack.pop().ok_or(EvalError::MalformedExpression)?;
+                stack.push(Value::Int(-expect_int(val, Op::Neg)?));
+            }
+            Token::Operation(op) => {
+                let rhs = stack.pop().ok_or(EvalError::MalformedExpression)?;
+                let lhs = stack.pop().ok_or(EvalError::MalformedExpression)?;
+                stack.push(apply(*op, lhs, rhs)?);
+            }
+            Token::LeftParen | Token::RightParen => {
+                unreachable!("{:?} must not appear in parsed RPN", tok)
+            }
+            Token::Keyword(_) | Token::Assign | Token::Semicolon => {
+                unreachable!("{:?} must not appear in parsed RPN", tok)
+            }
+            Token::EOF => break,
+        }
+    }
+
+    match stack.pop() {
+        Some(result) if stack.is_empty() => Ok(result),
+        _ => Err(EvalError::MalformedExpression),
+    }
+}
+
+/// Unwraps an `Int`, or reports a `TypeError` naming the operator that
+/// expected one.
+fn expect_int(value: Value, op: Op) -> Result<i32, EvalError> {
+    match value {
+        Value::Int(n) => Ok(n),
+        Value::Bool(b) => Err(EvalError::TypeError(format!(
+            "{:?} does not apply to the boolean {}",
+            op, b
+        ))),
+    }
+}
+
+/// Applies a binary operator to its already-popped left- and right-hand
+/// operands.
+fn apply(op: Op, lhs: Value, rhs: Value) -> Result<Value, EvalError> {
+    use Op::*;
+
+    match op {
+        Add | Sub | Mul | Div | Pow => {
+            let lhs = expect_int(lhs, op)?;
+            let rhs = expect_int(rhs, op)?;
+            let result = match op {
+                Add => lhs + rhs,
+                Sub => lhs - rhs,
+                Mul => lhs * rhs,
+                Div => {
+                    if rhs == 0 {
+                        return Err(EvalError::DivisionByZero);
+                    }
+                    lhs / rhs
+                }
+                Pow => {
+                    let rhs: u32 = rhs.try_into().map_err(|_| EvalError::NegativeExponent)?;
+                    lhs.pow(rhs)
+                }
+                _ => unreachable!("handled above"),
+            };
+            Ok(Value::Int(result))
+        }
+        Lt | Gt | Le | Ge => {
+            let lhs = expect_int(lhs, op)?;
+            let rhs = expect_int(rhs, op)?;
+            Ok(Value::Bool(match op {
+                Lt => lhs < rhs,
+                Gt => lhs > rhs,
+                Le => lhs <= rhs,
+                Ge => lhs >= rhs,
+                _ => unreachable!("handled above"),
+            }))
+        }
+        Eq | NotEq => {
+            let equal = match (lhs, rhs) {
+                (Value::Int(l), Value::Int(r)) => l == r,
+                (Value::Bool(l), Value::Bool(r)) => l == r,
+                (l, r) => {
+                    return Err(EvalError::TypeError(format!(
+                        "cannot compare {:?} and {:?}",
+                        l, r
+                    )))
+                }
+            };
+            Ok(Value::Bool(if op == Eq { equal } else { !equal }))
+        }
+        Neg => unreachable!("Neg is unary and handled in eval"),
+    }
+}
+
+/// All the ways running a full program can fail: either a statement failed
+/// to parse, or evaluating it failed.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ProgramError {
+    Parsing(ParsingError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::Parsing(why) => write!(f, "{}", why),
+            ProgramError::Eval(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl Error for ProgramError {}
+
+/// Runs a full program: zero or more `let NAME = expr;` statements followed
+/// by a trailing expression, threading `env` across all of them so later
+/// statements can read what earlier ones bound.
+pub fn run(tokens: Vec<Spanned<Token>>, env: &mut HashMap<String, Value>) -> Result<Value, ProgramError> {
+    let mut statements: Vec<Vec<Spanned<Token>>> = Vec::new();
+    let mut current = Vec::new();
+    for tok in tokens {
+        if tok.value == Token::Semicolon {
+            statements.push(std::mem::take(&mut current));
+        } else {
+            current.push(tok);
+        }
+    }
+    statements.push(current);
+
+    let tail = statements.pop().expect("always at least one statement");
+    for stmt in statements {
+        let (name, expr) = parse_let(&stmt)?;
+        let postfix = parse(expr).map_err(ProgramError::Parsing)?;
+        let plain: Vec<Token> = postfix.into_iter().map(|s| s.value).collect();
+        let value = eval(&plain, env).map_err(ProgramError::Eval)?;
+        env.insert(name, value);
+    }
+
+    let postfix = parse(tail).map_err(ProgramError::Parsing)?;
+    let plain: Vec<Token> = postfix.into_iter().map(|s| s.value).collect();
+    eval(&plain, env).map_err(ProgramError::Eval)
+}
+
+/// Splits a `let NAME = expr` statement into the bound name and the
+/// remaining tokens that make up `expr`.
+fn parse_let(stmt: &[Spanned<Token>]) -> Result<(String, Vec<Spanned<Token>>), ProgramError> {
+    match stmt {
+        [Spanned { value: Token::Keyword(Keyword::Let), .. }, Spanned { value: Token::Ident(name), .. }, Spanned { value: Token::Assign, .. }, rest @ ..] =>
+        {
+            Ok((name.clone(), rest.to_vec()))
+        }
+        _ => {
+            let span = stmt.first().map(|tok| tok.span.clone()).unwrap_or(0..0);
+            Err(ProgramError::Parsing(ParsingError::BadInput { span }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex;
+
+    fn eval_str(input: &str) -> Result<Value, EvalError> {
+        let postfix: Vec<Token> = parse(lex(input).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|s| s.value)
+            .collect();
+        eval(&postfix, &HashMap::new())
+    }
+
+    fn run_str(input: &str) -> Result<Value, ProgramError> {
+        let mut env = HashMap::new();
+        run(lex(input).unwrap(), &mut env)
+    }
+
+    #[test]
+    fn basic_eval() {
+        // `*`/`/` are left-associative: (4 * 351) / 6 = 234, then 3 + 234.
+        assert_eq!(eval_str("3 + 4 * (420 - 69) / (2 + 4)"), Ok(Value::Int(237)));
+        assert_eq!(eval_str("420 + 69"), Ok(Value::Int(489)));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval_str("1 / 0"), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn unary_minus_and_pow() {
+        assert_eq!(eval_str("-3 + 4"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("2 ^ 3 ^ 2"), Ok(Value::Int(512)));
+        assert_eq!(eval_str("-(3 + 4)"), Ok(Value::Int(-7)));
+    }
+
+    #[test]
+    fn negative_exponent_is_an_error() {
+        assert_eq!(eval_str("2 ^ -1"), Err(EvalError::NegativeExponent));
+    }
+
+    #[test]
+    fn same_precedence_operators_are_left_associative() {
+        assert_eq!(eval_str("3 - 4 - 5"), Ok(Value::Int(-6)));
+        assert_eq!(eval_str("20 / 2 / 5"), Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn malformed_expression() {
+        assert_eq!(
+            eval(&[Token::Operation(Op::Add), Token::Number(1)], &HashMap::new()),
+            Err(EvalError::MalformedExpression)
+        );
+    }
+
+    #[test]
+    fn let_binds_a_name_the_tail_expression_can_use() {
+        assert_eq!(run_str("let x = 3 + 4; x * 2"), Ok(Value::Int(14)));
+    }
+
+    #[test]
+    fn later_lets_can_read_earlier_ones() {
+        assert_eq!(run_str("let x = 1; let y = x + 1; x + y"), Ok(Value::Int(3)));
+    }
+
+    #[test]
+    fn let_can_bind_a_negative_literal() {
+        assert_eq!(run_str("let x = -3; x + 10"), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn undefined_variable_is_an_error() {
+        assert_eq!(
+            run_str("x + 1"),
+            Err(ProgramError::Eval(EvalError::UndefinedVariable("x".to_string())))
+        );
+    }
+
+    #[test]
+    fn bool_literals_and_comparisons() {
+        assert_eq!(eval_str("true"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("1 < 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("1 >= 2"), Ok(Value::Bool(false)));
+        assert_eq!(eval_str("3 == 3"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("3 != 3"), Ok(Value::Bool(false)));
+        assert_eq!(eval_str("true == (1 < 2)"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn comparisons_bind_looser_than_addition() {
+        // 1 + 2 < 4 parses as (1 + 2) < 4, i.e. 3 < 4.
+        assert_eq!(eval_str("1 + 2 < 4"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn adding_booleans_is_a_type_error() {
+        assert!(matches!(eval_str("true + false"), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn comparing_unlike_types_is_a_type_error() {
+        assert!(matches!(eval_str("1 == true"), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_str("1 < true"), Err(EvalError::TypeError(_))));
+    }
+}
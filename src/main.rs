@@ -1,14 +1,30 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::ops::Range;
 
-/// Mathematical operations that our compiler can do.
+mod ast;
+mod codegen;
+mod eval;
+
+/// Mathematical and comparison operations that our compiler can do. `Neg` is
+/// the unary prefix minus (e.g. the `-` in `-5`), every other variant is a
+/// binary operator.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 enum Op {
     Mul,
     Div,
     Add,
     Sub,
+    Pow,
+    Neg,
+    Eq,
+    NotEq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
 }
 
 impl Op {
@@ -16,39 +32,87 @@ impl Op {
         use Op::*;
 
         match self {
+            Neg => PREC_NEG,
+            Pow => PREC_POW,
             Mul | Div => PREC_MUL,
             Add | Sub => PREC_ADD,
+            Eq | NotEq | Lt | Gt | Le | Ge => PREC_CMP,
         }
     }
+
+    /// `Pow` is right-associative, and so is the prefix `Neg` (`- -3` is
+    /// `-(-3)`); every other operator groups left-to-right.
+    fn is_right_associative(&self) -> bool {
+        matches!(self, Op::Pow | Op::Neg)
+    }
 }
 
-const PREC_MUL: i32 = 3;
+// Comparisons bind more loosely than `+`/`-`, so `1 + 2 < 4` parses as
+// `(1 + 2) < 4` rather than `1 + (2 < 4)`.
+const PREC_CMP: i32 = 1;
 const PREC_ADD: i32 = 2;
+const PREC_MUL: i32 = 3;
+// `Pow` is right-associative: `Op::is_right_associative` tells the
+// shunting-yard loop below not to pop an equal-precedence predecessor, so
+// two `^`s never pop each other and naturally group to the right.
+const PREC_POW: i32 = 4;
+// Unary minus binds tighter than any binary operator.
+const PREC_NEG: i32 = 5;
+
+/// Reserved words, recognized by `lex` after it has lexed an identifier.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Keyword {
+    Let,
+}
 
 /// All of the possible tokens for the compiler, this limits the compiler
-/// to simple math expressions.
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+/// to simple math, comparison and boolean expressions plus `let` bindings.
+#[derive(Debug, Eq, PartialEq, Clone)]
 enum Token {
     EOF,
     Number(i32),
+    Bool(bool),
     Operation(Op),
     LeftParen,
     RightParen,
+    Ident(String),
+    Keyword(Keyword),
+    Assign,
+    /// Separates statements on a single line of input, e.g. the `;` in
+    /// `let x = 1; x + 2`. Distinct from `EOF`, which marks the true end of
+    /// the input.
+    Semicolon,
+}
+
+/// A value paired with the byte range of the input it came from.
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Spanned<T> {
+    value: T,
+    span: Range<usize>,
 }
 
 /// All possible parsing errors.
 #[derive(Debug, Eq, PartialEq)]
 pub enum ParsingError {
-    BadInput,
-    NoMatchingParen,
+    BadInput { span: Range<usize> },
+    NoMatchingParen { span: Range<usize> },
+}
+
+impl ParsingError {
+    fn span(&self) -> Range<usize> {
+        match self {
+            ParsingError::BadInput { span } => span.clone(),
+            ParsingError::NoMatchingParen { span } => span.clone(),
+        }
+    }
 }
 
 // Errors need to be displayable.
 impl fmt::Display for ParsingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ParsingError::BadInput => write!(f, "something in your input is bad, good luck"),
-            ParsingError::NoMatchingParen => write!(f, "no matching paren found"),
+            ParsingError::BadInput { .. } => write!(f, "something in your input is bad, good luck"),
+            ParsingError::NoMatchingParen { .. } => write!(f, "no matching paren found"),
         }
     }
 }
@@ -62,55 +126,170 @@ impl Into<io::Error> for ParsingError {
     }
 }
 
-/// Turns a string of input into a slice of tokens. This goes over every character
-/// in the string and combines numbers together.
-fn lex(input: &str) -> Result<Vec<Token>, ParsingError> {
+/// Renders `input` with a caret (`^`) underline under the span where `err`
+/// occurred, e.g.:
+///
+/// ```text
+/// 3 + @ * 4
+///     ^
+/// something in your input is bad, good luck
+/// ```
+fn render_error(input: &str, err: &ParsingError) -> String {
+    let span = err.span();
+    let underline: String = " ".repeat(span.start) + &"^".repeat((span.end - span.start).max(1));
+
+    format!("{}\n{}\n{}", input, underline, err)
+}
+
+/// Turns a string of input into a slice of tokens paired with the byte span
+/// each one came from. This goes over every character in the string and
+/// combines numbers together.
+fn lex(input: &str) -> Result<Vec<Spanned<Token>>, ParsingError> {
     use Op::*;
     use Token::*;
-    let mut result: Vec<Token> = Vec::new();
+    let mut result: Vec<Spanned<Token>> = Vec::new();
+    // Peekable so `==`, `!=`, `<=`, and `>=` can be told apart from their
+    // one-character prefixes without re-lexing.
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((pos, character)) = chars.next() {
+        let end = pos + character.len_utf8();
 
-    for character in input.chars() {
         match character {
             // Skip whitespace
             ' ' => continue,
 
-            // Ending characters
-            ';' | '\n' => {
-                result.push(EOF);
+            // The true end of the input.
+            '\n' => {
+                result.push(Spanned { value: EOF, span: pos..end });
                 break;
             }
 
+            // Separates statements within a single line of input.
+            ';' => result.push(Spanned { value: Semicolon, span: pos..end }),
+
             // Math operations
-            '*' => result.push(Operation(Mul)),
-            '/' => result.push(Operation(Div)),
-            '+' => result.push(Operation(Add)),
-            '-' => result.push(Operation(Sub)),
+            '*' => result.push(Spanned { value: Operation(Mul), span: pos..end }),
+            '/' => result.push(Spanned { value: Operation(Div), span: pos..end }),
+            '+' => result.push(Spanned { value: Operation(Add), span: pos..end }),
+            '^' => result.push(Spanned { value: Operation(Pow), span: pos..end }),
+
+            // `=` is `Assign` unless it's immediately followed by another
+            // `=`, in which case the pair together is the `Eq` operator.
+            '=' => match chars.peek() {
+                Some((_, '=')) => {
+                    chars.next();
+                    result.push(Spanned { value: Operation(Eq), span: pos..end + 1 });
+                }
+                _ => result.push(Spanned { value: Assign, span: pos..end }),
+            },
+
+            // `!` only appears as the first half of `!=`; bare `!` is bad
+            // input since there's no unary boolean negation yet.
+            '!' => match chars.peek() {
+                Some((_, '=')) => {
+                    chars.next();
+                    result.push(Spanned { value: Operation(NotEq), span: pos..end + 1 });
+                }
+                _ => return Err(ParsingError::BadInput { span: pos..end }),
+            },
+
+            // `<`/`>` are `Lt`/`Gt` unless followed by `=`, which makes them
+            // `Le`/`Ge`.
+            '<' => match chars.peek() {
+                Some((_, '=')) => {
+                    chars.next();
+                    result.push(Spanned { value: Operation(Le), span: pos..end + 1 });
+                }
+                _ => result.push(Spanned { value: Operation(Lt), span: pos..end }),
+            },
+            '>' => match chars.peek() {
+                Some((_, '=')) => {
+                    chars.next();
+                    result.push(Spanned { value: Operation(Ge), span: pos..end + 1 });
+                }
+                _ => result.push(Spanned { value: Operation(Gt), span: pos..end }),
+            },
+
+            // A `-` is unary negation at the start of input or right after
+            // another operator, a `(`, a `=`, or a `;`; otherwise it's
+            // binary subtraction.
+            '-' => {
+                let op = match result.last() {
+                    None
+                    | Some(Spanned { value: Operation(_), .. })
+                    | Some(Spanned { value: LeftParen, .. })
+                    | Some(Spanned { value: Assign, .. })
+                    | Some(Spanned { value: Semicolon, .. }) => Neg,
+                    _ => Sub,
+                };
+                result.push(Spanned { value: Operation(op), span: pos..end });
+            }
 
             // Parentheses
-            '(' => result.push(LeftParen),
-            ')' => result.push(RightParen),
+            '(' => result.push(Spanned { value: LeftParen, span: pos..end }),
+            ')' => result.push(Spanned { value: RightParen, span: pos..end }),
 
             // Numbers
             '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
                 let num: i32 = (character as u8 - '0' as u8) as i32;
 
                 match result.pop() {
-                    Some(Number(i)) => {
-                        result.push(Number((i * 10) + num));
+                    Some(Spanned { value: Number(i), span }) => {
+                        result.push(Spanned {
+                            value: Number((i * 10) + num),
+                            span: span.start..end,
+                        });
+                    }
+                    // Only extend an identifier if it's directly adjacent
+                    // (no whitespace), so `let x` doesn't lex as `letx`.
+                    Some(Spanned { value: Ident(mut name), span }) if span.end == pos => {
+                        name.push(character);
+                        result.push(Spanned { value: Ident(name), span: span.start..end });
                     }
                     Some(last) => {
                         result.push(last);
-                        result.push(Number(num));
+                        result.push(Spanned { value: Number(num), span: pos..end });
                     }
                     None => {
-                        result.push(Number(num));
+                        result.push(Spanned { value: Number(num), span: pos..end });
                         continue;
                     }
                 }
             }
 
+            // Identifiers: a letter or underscore starts one, further
+            // letters, digits, or underscores extend it. Checked for
+            // being a keyword once lexing finishes.
+            'a'..='z' | 'A'..='Z' | '_' => match result.pop() {
+                // Only extend if directly adjacent; see the `Number` arm.
+                Some(Spanned { value: Ident(mut name), span }) if span.end == pos => {
+                    name.push(character);
+                    result.push(Spanned { value: Ident(name), span: span.start..end });
+                }
+                Some(last) => {
+                    result.push(last);
+                    result.push(Spanned { value: Ident(character.to_string()), span: pos..end });
+                }
+                None => {
+                    result.push(Spanned { value: Ident(character.to_string()), span: pos..end });
+                    continue;
+                }
+            },
+
             // Everything else is bad input
-            _ => return Err(ParsingError::BadInput),
+            _ => return Err(ParsingError::BadInput { span: pos..end }),
+        }
+    }
+
+    for tok in result.iter_mut() {
+        if let Ident(name) = &tok.value {
+            match name.as_str() {
+                "let" => tok.value = Keyword(crate::Keyword::Let),
+                "true" => tok.value = Bool(true),
+                "false" => tok.value = Bool(false),
+                _ => {}
+            }
         }
     }
 
@@ -135,56 +314,72 @@ impl<T: Clone> Stack<T> for Vec<T> {
     }
 }
 
-fn tilt_until(operators: &mut Vec<Token>, output: &mut Vec<Token>, stop: Token) -> bool {
+/// Pops `operators` into `output` until `stop` is found, returning the
+/// `stop` token if one was found so its span can be reported.
+fn tilt_until(
+    operators: &mut Vec<Spanned<Token>>,
+    output: &mut Vec<Spanned<Token>>,
+    stop: Token,
+) -> Option<Spanned<Token>> {
     while let Some(token) = operators.pop() {
-        if token == stop {
-            return true;
+        if token.value == stop {
+            return Some(token);
         }
         output.push(token)
     }
-    false
+    None
 }
 
 /// Takes a list of Tokens and runs the [Shunting-yard](https://en.wikipedia.org/wiki/Shunting-yard_algorithm)
 /// algorithm to turn infix notation into postfix notation.
-fn parse(tokens: Vec<Token>) -> Result<Vec<Token>, ParsingError> {
+fn parse(tokens: Vec<Spanned<Token>>) -> Result<Vec<Spanned<Token>>, ParsingError> {
     use Token::*;
-    let mut result: Vec<Token> = vec![];
-    let mut stack: Vec<Token> = vec![];
+    let mut result: Vec<Spanned<Token>> = vec![];
+    let mut stack: Vec<Spanned<Token>> = vec![];
 
     for tok in tokens {
-        match tok {
-            Number(_) => result.push(tok),
+        match &tok.value {
+            Number(_) | Bool(_) | Ident(_) => result.push(tok),
             LeftParen => stack.push(tok),
             RightParen => {
-                if !tilt_until(&mut stack, &mut result, LeftParen) {
-                    return Err(ParsingError::NoMatchingParen);
+                if tilt_until(&mut stack, &mut result, LeftParen).is_none() {
+                    return Err(ParsingError::NoMatchingParen { span: tok.span });
                 }
             }
             Operation(op) => {
+                let op = *op;
                 while let Some(top) = stack.top() {
-                    match top {
+                    match &top.value {
                         LeftParen => break,
                         Operation(top_op) => {
                             let p = top_op.precedence();
                             let q = op.precedence();
-                            if p > q {
+                            // Left-associative operators also pop an
+                            // equal-precedence predecessor (`3 - 4 - 5` must
+                            // group as `(3 - 4) - 5`); `^` and the prefix `-`
+                            // do not, which is what makes them right-associative.
+                            if p > q || (p == q && !op.is_right_associative()) {
                                 result.push(stack.pop().unwrap());
                             } else {
                                 break;
                             }
                         }
-                        _ => unreachable!("{:?} must not be on the stack", top),
+                        _ => unreachable!("{:?} must not be on the stack", top.value),
                     }
                 }
                 stack.push(tok);
             }
             EOF => break,
+            // `parse` only handles a single expression; statement-level
+            // syntax is peeled off by `eval::run` before this ever runs.
+            Keyword(_) | Assign | Semicolon => {
+                return Err(ParsingError::BadInput { span: tok.span });
+            }
         }
     }
 
-    if tilt_until(&mut stack, &mut result, LeftParen) {
-        return Err(ParsingError::NoMatchingParen);
+    if let Some(unmatched) = tilt_until(&mut stack, &mut result, LeftParen) {
+        return Err(ParsingError::NoMatchingParen { span: unmatched.span });
     }
 
     assert!(stack.is_empty());
@@ -192,13 +387,54 @@ fn parse(tokens: Vec<Token>) -> Result<Vec<Token>, ParsingError> {
 }
 
 fn main() -> io::Result<()> {
+    let eval_mode = std::env::args().any(|arg| arg == "--eval");
+    let ast_mode = std::env::args().any(|arg| arg == "--ast");
+
     let stdin = io::stdin();
     let mut input = String::new();
     stdin.read_line(&mut input)?;
 
-    let tokens = lex(input.as_str()).map_err(|why| io::Error::new(io::ErrorKind::Other, why))?;
-    let parsed_tokens = parse(tokens).map_err(|why| io::Error::new(io::ErrorKind::Other, why))?;
-    println!("{:#?}", parsed_tokens);
+    let tokens = match lex(input.as_str()) {
+        Ok(tokens) => tokens,
+        Err(why) => {
+            eprintln!("{}", render_error(&input, &why));
+            return Err(why.into());
+        }
+    };
+
+    if eval_mode {
+        let mut env = HashMap::new();
+        let result =
+            eval::run(tokens, &mut env).map_err(|why| io::Error::new(io::ErrorKind::Other, why))?;
+        println!("{}", result);
+        return Ok(());
+    }
+
+    if ast_mode {
+        let tree = ast::parse_expr(&tokens).map_err(|why| {
+            eprintln!("{}", render_error(&input, &why));
+            io::Error::new(io::ErrorKind::Other, why)
+        })?;
+        println!("{:#?}", tree);
+        return Ok(());
+    }
+
+    let parsed_tokens = match parse(tokens) {
+        Ok(tokens) => tokens,
+        Err(why) => {
+            eprintln!("{}", render_error(&input, &why));
+            return Err(why.into());
+        }
+    };
+    let plain_tokens: Vec<Token> = parsed_tokens.into_iter().map(|s| s.value).collect();
+
+    println!("{:#?}", plain_tokens);
+
+    let instrs =
+        codegen::compile(&plain_tokens).map_err(|why| io::Error::new(io::ErrorKind::Other, why))?;
+    for instr in &instrs {
+        println!("{}", instr);
+    }
 
     Ok(())
 }
@@ -207,18 +443,32 @@ fn main() -> io::Result<()> {
 mod tests {
     use super::{Op::*, Token::*, *};
 
+    /// Strips spans so tests can compare against plain token lists.
+    fn values(tokens: Vec<Spanned<Token>>) -> Vec<Token> {
+        tokens.into_iter().map(|s| s.value).collect()
+    }
+
+    /// Wraps plain tokens with a dummy span, for tests that don't care where
+    /// each token came from.
+    fn spanned(tokens: Vec<Token>) -> Vec<Spanned<Token>> {
+        tokens
+            .into_iter()
+            .map(|value| Spanned { value, span: 0..0 })
+            .collect()
+    }
+
     #[test]
     fn basic_lexing() {
         assert!(lex("420 + 69").is_ok());
-        assert!(lex("tacos are tasty").is_err());
+        assert!(lex("3 + @ * 4").is_err());
 
         assert_eq!(
-            lex("420 + 69"),
-            Ok(vec![Number(420), Operation(Add), Number(69)])
+            values(lex("420 + 69").unwrap()),
+            vec![Number(420), Operation(Add), Number(69)]
         );
         assert_eq!(
-            lex("(30 + 560) / 4"),
-            Ok(vec![
+            values(lex("(30 + 560) / 4").unwrap()),
+            vec![
                 LeftParen,
                 Number(30),
                 Operation(Add),
@@ -226,28 +476,164 @@ mod tests {
                 RightParen,
                 Operation(Div),
                 Number(4)
-            ])
+            ]
+        );
+    }
+
+    #[test]
+    fn lexing_tracks_spans() {
+        let tokens = lex("12 + 345").unwrap();
+        assert_eq!(tokens[0], Spanned { value: Number(12), span: 0..2 });
+        assert_eq!(tokens[1], Spanned { value: Operation(Add), span: 3..4 });
+        assert_eq!(tokens[2], Spanned { value: Number(345), span: 5..8 });
+    }
+
+    #[test]
+    fn minus_is_unary_or_binary_by_context() {
+        assert_eq!(
+            values(lex("-5").unwrap()),
+            vec![Operation(Neg), Number(5)]
+        );
+        assert_eq!(
+            values(lex("3 - 5").unwrap()),
+            vec![Number(3), Operation(Sub), Number(5)]
+        );
+        assert_eq!(
+            values(lex("3 * -5").unwrap()),
+            vec![Number(3), Operation(Mul), Operation(Neg), Number(5)]
+        );
+        assert_eq!(
+            values(lex("(-5)").unwrap()),
+            vec![LeftParen, Operation(Neg), Number(5), RightParen]
+        );
+    }
+
+    #[test]
+    fn minus_is_unary_right_after_assign_or_semicolon() {
+        assert_eq!(
+            values(lex("let x = -3;").unwrap()),
+            vec![
+                Keyword(crate::Keyword::Let),
+                Ident("x".to_string()),
+                Assign,
+                Operation(Neg),
+                Number(3),
+                Semicolon,
+            ]
+        );
+        assert_eq!(
+            values(lex("let x = 1; -x").unwrap()),
+            vec![
+                Keyword(crate::Keyword::Let),
+                Ident("x".to_string()),
+                Assign,
+                Number(1),
+                Semicolon,
+                Operation(Neg),
+                Ident("x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn identifiers_and_let_are_lexed() {
+        assert_eq!(
+            values(lex("let x = 5").unwrap()),
+            vec![Keyword(crate::Keyword::Let), Ident("x".to_string()), Assign, Number(5)]
+        );
+        assert_eq!(
+            values(lex("x1 + _y2").unwrap()),
+            vec![Ident("x1".to_string()), Operation(Add), Ident("_y2".to_string())]
+        );
+    }
+
+    #[test]
+    fn semicolon_separates_statements_but_does_not_end_input() {
+        assert_eq!(
+            values(lex("let x = 1; x").unwrap()),
+            vec![
+                Keyword(crate::Keyword::Let),
+                Ident("x".to_string()),
+                Assign,
+                Number(1),
+                Semicolon,
+                Ident("x".to_string()),
+            ]
         );
     }
 
+    #[test]
+    fn comparison_operators_are_lexed() {
+        assert_eq!(
+            values(lex("1 == 2").unwrap()),
+            vec![Number(1), Operation(Eq), Number(2)]
+        );
+        assert_eq!(
+            values(lex("1 != 2").unwrap()),
+            vec![Number(1), Operation(NotEq), Number(2)]
+        );
+        assert_eq!(
+            values(lex("1 < 2").unwrap()),
+            vec![Number(1), Operation(Lt), Number(2)]
+        );
+        assert_eq!(
+            values(lex("1 > 2").unwrap()),
+            vec![Number(1), Operation(Gt), Number(2)]
+        );
+        assert_eq!(
+            values(lex("1 <= 2").unwrap()),
+            vec![Number(1), Operation(Le), Number(2)]
+        );
+        assert_eq!(
+            values(lex("1 >= 2").unwrap()),
+            vec![Number(1), Operation(Ge), Number(2)]
+        );
+    }
+
+    #[test]
+    fn bool_literals_are_lexed() {
+        assert_eq!(values(lex("true").unwrap()), vec![Bool(true)]);
+        assert_eq!(values(lex("false").unwrap()), vec![Bool(false)]);
+        assert_eq!(
+            values(lex("true == false").unwrap()),
+            vec![Bool(true), Operation(Eq), Bool(false)]
+        );
+    }
+
+    #[test]
+    fn bad_input_reports_its_span() {
+        match lex("3 + @ * 4") {
+            Err(err @ ParsingError::BadInput { .. }) => assert_eq!(err.span(), 4..5),
+            other => panic!("expected a BadInput error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn render_error_underlines_the_span() {
+        let err = lex("3 + @ * 4").unwrap_err();
+        assert_eq!(render_error("3 + @ * 4", &err), "3 + @ * 4\n    ^\nsomething in your input is bad, good luck");
+    }
+
     #[test]
     fn basic_parsing() {
         // things that should fail
-        assert!(parse(vec![LeftParen]).is_err());
-        assert!(parse(vec![RightParen]).is_err());
+        assert!(parse(spanned(vec![LeftParen])).is_err());
+        assert!(parse(spanned(vec![RightParen])).is_err());
 
         // basic infix expression with parens
         assert_eq!(
-            parse(vec![
-                Number(3),
-                Operation(Add),
-                LeftParen,
-                Number(4),
-                Operation(Mul),
-                Number(5),
-                RightParen
-            ])
-            .unwrap(),
+            values(
+                parse(spanned(vec![
+                    Number(3),
+                    Operation(Add),
+                    LeftParen,
+                    Number(4),
+                    Operation(Mul),
+                    Number(5),
+                    RightParen
+                ]))
+                .unwrap()
+            ),
             vec![
                 Number(3),
                 Number(4),
@@ -264,7 +650,7 @@ mod tests {
         let maybe_tree = parse(lex("3 + 4 * (420 - 69) / (2 + 4)").unwrap());
         assert!(maybe_tree.is_ok());
 
-        let tree = maybe_tree.unwrap();
+        let tree = values(maybe_tree.unwrap());
         assert_eq!(
             tree,
             vec![
@@ -273,11 +659,11 @@ mod tests {
                 Number(420),
                 Number(69),
                 Operation(Sub),
+                Operation(Mul),
                 Number(2),
                 Number(4),
                 Operation(Add),
                 Operation(Div),
-                Operation(Mul),
                 Operation(Add),
             ]
         )